@@ -0,0 +1,194 @@
+// Client-side filtering and aggregation for `datapoint list`/`datapoint aggregate`, since the
+// Beeminder API has no server-side equivalent.
+use crate::Datapoint;
+use anyhow::anyhow;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::NaiveDate;
+use chrono::Utc;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Client-side filter applied to a `Vec<Datapoint>` after fetching it from the API.
+pub struct Filter {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub min_value: Option<f64>,
+    pub max_value: Option<f64>,
+    pub comment_matches: Option<Regex>,
+}
+
+impl Filter {
+    fn matches(&self, point: &Datapoint) -> bool {
+        if let Some(since) = self.since {
+            if point.timestamp < since {
+                return false;
+            }
+        }
+        if let Some(until) = self.until {
+            if point.timestamp >= until {
+                return false;
+            }
+        }
+        if let Some(min) = self.min_value {
+            if point.value < min {
+                return false;
+            }
+        }
+        if let Some(max) = self.max_value {
+            if point.value > max {
+                return false;
+            }
+        }
+        if let Some(re) = &self.comment_matches {
+            if !re.is_match(point.comment.as_deref().unwrap_or("")) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub fn filter_datapoints(points: Vec<Datapoint>, filter: &Filter) -> Vec<Datapoint> {
+    points.into_iter().filter(|p| filter.matches(p)).collect()
+}
+
+/// Parses `YYYY-MM-DD`, or a humantime duration like `"7d"` taken relative to now.
+///
+/// A bare date is midnight at the start of that day, unless `end_inclusive` is set, in which case
+/// it's midnight at the start of the *next* day — so an `--until` bound includes the whole day
+/// named rather than excluding nearly all of it.
+pub fn parse_time_bound(s: &str, end_inclusive: bool) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let date = if end_inclusive { date.succ() } else { date };
+        return Ok(DateTime::<Utc>::from_utc(date.and_hms(0, 0, 0), Utc));
+    }
+    let duration = chrono::Duration::from_std(humantime::parse_duration(s)?)?;
+    Ok(Utc::now() - duration)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Bucket {
+    Day,
+    Week,
+    Month,
+}
+
+impl FromStr for Bucket {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "day" => Ok(Bucket::Day),
+            "week" => Ok(Bucket::Week),
+            "month" => Ok(Bucket::Month),
+            other => Err(anyhow!(
+                "Unknown bucket '{}', expected day, week, or month",
+                other
+            )),
+        }
+    }
+}
+
+impl Bucket {
+    fn key(&self, timestamp: DateTime<Utc>) -> String {
+        match self {
+            Bucket::Day => timestamp.format("%Y-%m-%d").to_string(),
+            Bucket::Week => {
+                let iso = timestamp.iso_week();
+                format!("{}-W{:02}", iso.year(), iso.week())
+            }
+            Bucket::Month => timestamp.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AggregateBucket {
+    pub bucket: String,
+    pub sum: f64,
+    pub count: usize,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+pub fn aggregate(points: &[Datapoint], bucket: Bucket) -> Vec<AggregateBucket> {
+    let mut groups: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for point in points {
+        groups
+            .entry(bucket.key(point.timestamp))
+            .or_default()
+            .push(point.value);
+    }
+    groups
+        .into_iter()
+        .map(|(key, values)| {
+            let count = values.len();
+            let sum: f64 = values.iter().sum();
+            AggregateBucket {
+                bucket: key,
+                sum,
+                count,
+                mean: sum / count as f64,
+                min: values.iter().cloned().fold(f64::INFINITY, f64::min),
+                max: values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum OutputFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            other => Err(anyhow!(
+                "Unknown format '{}', expected table, csv, or json",
+                other
+            )),
+        }
+    }
+}
+
+pub fn render(aggregates: &[AggregateBucket], format: OutputFormat) -> Result<String> {
+    match format {
+        OutputFormat::Json => Ok(serde_json::to_string(aggregates)?),
+        OutputFormat::Csv => {
+            let mut out = String::from("bucket,sum,count,mean,min,max\n");
+            for a in aggregates {
+                out.push_str(&format!(
+                    "{},{},{},{},{},{}\n",
+                    a.bucket, a.sum, a.count, a.mean, a.min, a.max
+                ));
+            }
+            Ok(out)
+        }
+        OutputFormat::Table => {
+            let mut out = format!(
+                "{:<10} {:>10} {:>6} {:>10} {:>10} {:>10}\n",
+                "bucket", "sum", "count", "mean", "min", "max"
+            );
+            for a in aggregates {
+                out.push_str(&format!(
+                    "{:<10} {:>10.2} {:>6} {:>10.2} {:>10.2} {:>10.2}\n",
+                    a.bucket, a.sum, a.count, a.mean, a.min, a.max
+                ));
+            }
+            Ok(out)
+        }
+    }
+}
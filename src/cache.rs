@@ -0,0 +1,123 @@
+// On-disk cache for read-only Beeminder API responses.
+//
+// Entries are stored as one JSON file per key under the platform cache directory, keyed by the
+// request URL path (e.g. `/goals/foo.json`). Each entry records when it was fetched so callers
+// can apply their own TTL policy.
+use anyhow::Context;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Outcome of a cache lookup.
+pub enum LookupStatus<T> {
+    Found(T),
+    NotFound,
+}
+
+/// A cache entry together with the time it was fetched from the network.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CacheEntry<T> {
+    pub fetched_at: DateTime<Utc>,
+    pub data: T,
+}
+
+impl<T> CacheEntry<T> {
+    pub fn new(data: T) -> Self {
+        CacheEntry {
+            fetched_at: Utc::now(),
+            data,
+        }
+    }
+
+    pub fn is_fresh(&self, ttl: chrono::Duration) -> bool {
+        Utc::now() - self.fetched_at < ttl
+    }
+}
+
+pub trait Cache {
+    type Item;
+
+    fn lookup(&self, key: &str) -> Result<LookupStatus<Self::Item>>;
+    fn store(&self, key: &str, item: &Self::Item) -> Result<()>;
+    fn invalidate(&self, key: &str) -> Result<()>;
+}
+
+/// A `Cache` backed by one JSON file per key under a directory.
+pub struct FileCache<T> {
+    dir: PathBuf,
+    _marker: PhantomData<T>,
+}
+
+impl<T> FileCache<T> {
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        Ok(FileCache {
+            dir,
+            _marker: PhantomData,
+        })
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        let sanitized = key.trim_start_matches('/').replace('/', "_");
+        self.dir.join(sanitized)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Cache for FileCache<T> {
+    type Item = CacheEntry<T>;
+
+    fn lookup(&self, key: &str) -> Result<LookupStatus<Self::Item>> {
+        let path = self.entry_path(key);
+        if !path.exists() {
+            return Ok(LookupStatus::NotFound);
+        }
+        let contents = std::fs::read_to_string(&path)?;
+        // A partial write from a crash mid-store, or a struct field change between versions,
+        // shouldn't turn one corrupt entry into a hard failure for every future read of this key.
+        match serde_json::from_str(&contents) {
+            Ok(entry) => Ok(LookupStatus::Found(entry)),
+            Err(err) => {
+                warn!("Discarding unreadable cache entry for {}: {}", key, err);
+                Ok(LookupStatus::NotFound)
+            }
+        }
+    }
+
+    fn store(&self, key: &str, item: &Self::Item) -> Result<()> {
+        // Write to a temporary file and rename into place so a crash mid-write never leaves a
+        // partially-written entry behind under the real key.
+        let path = self.entry_path(key);
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string(item)?)?;
+        std::fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn invalidate(&self, key: &str) -> Result<()> {
+        let path = self.entry_path(key);
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Directory where cache entries for `user` live, under the platform cache directory.
+///
+/// Scoped per user so that invoking the CLI with different `--profile`/`--username` values never
+/// serves one account's cached responses to another. Deliberately independent of `confy`'s own
+/// configuration path (used for the config file in `config.rs`) so the two don't need to agree on
+/// a single `confy` version.
+pub fn cache_dir(user: &str) -> Result<PathBuf> {
+    let base = dirs::cache_dir().context("could not determine a cache directory for this platform")?;
+    let sanitized_user = user.replace(std::path::MAIN_SEPARATOR, "_");
+    Ok(base.join("beeminder").join(sanitized_user))
+}
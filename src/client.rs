@@ -0,0 +1,74 @@
+// Client construction (proxy, DNS override) and retry-with-backoff helpers for transient
+// network failures.
+use anyhow::bail;
+use anyhow::Result;
+use log::info;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The only host this CLI ever talks to, so a DNS override only needs to name it.
+const BEEMINDER_HOST: &str = "www.beeminder.com";
+
+pub fn build_client(
+    user_agent: &str,
+    timeout: Option<Duration>,
+    proxy: Option<&str>,
+    resolve_override: Option<IpAddr>,
+) -> Result<reqwest::Client> {
+    let mut builder = reqwest::ClientBuilder::new().user_agent(user_agent);
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    if let Some(ip) = resolve_override {
+        builder = builder.resolve(BEEMINDER_HOST, SocketAddr::new(ip, 443));
+    }
+    Ok(builder.build()?)
+}
+
+const MAX_RETRIES: u32 = 3;
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(500);
+
+// Sends the request built by `build`, retrying transient 5xx responses and connection errors
+// with exponential backoff. `build` is called once per attempt so the request is rebuilt from
+// scratch each time rather than relying on `RequestBuilder::try_clone`.
+pub async fn send_with_retry(
+    build: impl Fn() -> reqwest::RequestBuilder,
+    backoff: Option<Duration>,
+) -> Result<reqwest::Response> {
+    let backoff = backoff.unwrap_or(DEFAULT_BACKOFF);
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let outcome = build().send().await;
+        let retryable = match &outcome {
+            Ok(response) => response.status().is_server_error(),
+            Err(err) => err.is_connect() || err.is_timeout(),
+        };
+        if !retryable || attempt > MAX_RETRIES {
+            return Ok(outcome?);
+        }
+        let wait = backoff * 2u32.pow(attempt - 1);
+        info!(
+            "Attempt {} of {} failed transiently, retrying in {:?}",
+            attempt,
+            MAX_RETRIES + 1,
+            wait
+        );
+        tokio::time::sleep(wait).await;
+    }
+}
+
+// Surfaces a non-2xx response as an error carrying Beeminder's JSON error body, instead of
+// letting a mutating command silently do nothing.
+pub async fn check_status(response: reqwest::Response) -> Result<()> {
+    if let Err(err) = response.error_for_status_ref() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        bail!("Beeminder API request failed with status {}: {} ({})", status, err, body);
+    }
+    Ok(())
+}
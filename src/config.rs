@@ -0,0 +1,139 @@
+// Confy-backed configuration: named credential profiles plus client-wide settings.
+//
+// The on-disk schema keeps the historical flat `username`/`auth_token` fields at the top level
+// (via `#[serde(flatten)]`) so existing config files keep loading unmodified as the `default`
+// profile, while new named profiles live alongside it under `[profiles.<name>]`.
+use anyhow::bail;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::Cli;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Profile {
+    pub username: Option<String>,
+    pub auth_token: Option<String>,
+    /// How long to wait for a response before giving up, e.g. `"30s"`.
+    #[serde(default)]
+    pub request_timeout: Option<String>,
+    /// Initial backoff between retries of a failed request, e.g. `"1s"`.
+    #[serde(default)]
+    pub retry_backoff: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Config {
+    #[serde(flatten)]
+    default: Profile,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+impl Config {
+    fn profile(&self, name: &str) -> Result<Profile> {
+        if name == "default" {
+            Ok(self.default.clone())
+        } else {
+            match self.profiles.get(name) {
+                Some(profile) => Ok(profile.clone()),
+                None => bail!("no profile named '{}' in config", name),
+            }
+        }
+    }
+}
+
+/// Where a resolved credential value ultimately came from, for `auth check` to report.
+#[derive(Debug, Clone)]
+pub enum CredentialSource {
+    Flag,
+    EnvVar,
+    Profile(String),
+}
+
+impl std::fmt::Display for CredentialSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CredentialSource::Flag => write!(f, "command-line flag"),
+            CredentialSource::EnvVar => write!(f, "environment variable"),
+            CredentialSource::Profile(name) => write!(f, "config profile \"{}\"", name),
+        }
+    }
+}
+
+// Resolves a single setting with the precedence flag > env var > config profile, reporting
+// which of the three supplied the value that was used.
+fn resolve(
+    flag: &Option<String>,
+    env_var: &str,
+    profile_value: Option<String>,
+    profile_name: &str,
+) -> (Option<String>, CredentialSource) {
+    if let Some(value) = flag {
+        (Some(value.to_string()), CredentialSource::Flag)
+    } else if let Ok(value) = std::env::var(env_var) {
+        (Some(value), CredentialSource::EnvVar)
+    } else {
+        (profile_value, CredentialSource::Profile(profile_name.to_string()))
+    }
+}
+
+/// Resolved authentication and client settings for a single invocation. Credentials give
+/// command-line flags and environment variables precedence over the selected profile; the
+/// timeout and retry backoff come from the selected profile only.
+#[derive(Debug)]
+pub struct Auth {
+    pub username: Option<String>,
+    pub username_source: CredentialSource,
+    pub auth_token: Option<String>,
+    pub token_source: CredentialSource,
+    pub request_timeout: Option<Duration>,
+    pub retry_backoff: Option<Duration>,
+}
+
+impl Auth {
+    // Load authentication information with the following order of preference, descending:
+    //
+    // - Command line argument (i.e. --username or --auth-token).
+    // - Environment variable (i.e. BEEMINDER_USERNAME).
+    // - The selected configuration profile (i.e. --profile, defaulting to "default").
+    //
+    // The request timeout and retry backoff are read from the selected profile only.
+    pub fn load(args: &Cli) -> Result<Self> {
+        let config: Config = confy::load("beeminder")?;
+        let profile_name = args.profile.as_deref().unwrap_or("default");
+        let profile = config.profile(profile_name)?;
+        let (username, username_source) = resolve(
+            &args.username,
+            "BEEMINDER_USERNAME",
+            profile.username,
+            profile_name,
+        );
+        let (auth_token, token_source) = resolve(
+            &args.auth_token,
+            "BEEMINDER_AUTH_TOKEN",
+            profile.auth_token,
+            profile_name,
+        );
+        let request_timeout = profile
+            .request_timeout
+            .as_deref()
+            .map(humantime::parse_duration)
+            .transpose()?;
+        let retry_backoff = profile
+            .retry_backoff
+            .as_deref()
+            .map(humantime::parse_duration)
+            .transpose()?;
+        Ok(Auth {
+            username,
+            username_source,
+            auth_token,
+            token_source,
+            request_timeout,
+            retry_backoff,
+        })
+    }
+}
@@ -10,11 +10,23 @@ use chrono::DateTime;
 use chrono::Utc;
 use log::info;
 use regex::Regex;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
 use std::io::prelude::*;
 use structopt::StructOpt;
 
+mod analytics;
+mod cache;
+mod client;
+mod config;
+
+use cache::Cache;
+use cache::CacheEntry;
+use cache::FileCache;
+use cache::LookupStatus;
+use config::Auth;
+
 #[derive(StructOpt)]
 enum GoalCommand {
     #[structopt(name = "list", about = "List all goals of a user")]
@@ -23,10 +35,75 @@ enum GoalCommand {
     Info { goal: String },
 }
 
+#[derive(StructOpt)]
+struct DatapointFilters {
+    #[structopt(
+        long,
+        about = "Only include datapoints at or after this time: YYYY-MM-DD or a relative humantime duration such as \"7d\""
+    )]
+    since: Option<String>,
+    #[structopt(
+        long,
+        about = "Only include datapoints at or before this time: YYYY-MM-DD or a relative humantime duration such as \"7d\""
+    )]
+    until: Option<String>,
+    #[structopt(long, about = "Only include datapoints with value >= this")]
+    min_value: Option<f64>,
+    #[structopt(long, about = "Only include datapoints with value <= this")]
+    max_value: Option<f64>,
+    #[structopt(long, about = "Only include datapoints whose comment matches this regex")]
+    comment_matches: Option<String>,
+}
+
+impl DatapointFilters {
+    fn into_filter(self) -> Result<analytics::Filter> {
+        Ok(analytics::Filter {
+            since: self
+                .since
+                .as_deref()
+                .map(|s| analytics::parse_time_bound(s, false))
+                .transpose()?,
+            until: self
+                .until
+                .as_deref()
+                .map(|s| analytics::parse_time_bound(s, true))
+                .transpose()?,
+            min_value: self.min_value,
+            max_value: self.max_value,
+            comment_matches: self
+                .comment_matches
+                .as_deref()
+                .map(Regex::new)
+                .transpose()?,
+        })
+    }
+}
+
 #[derive(StructOpt)]
 enum DatapointCommand {
     #[structopt(name = "list", about = "List datapoints of a goal")]
-    List { goal: String },
+    List {
+        goal: String,
+        #[structopt(flatten)]
+        filters: DatapointFilters,
+    },
+    #[structopt(
+        name = "aggregate",
+        about = "Group a goal's datapoints by day/week/month and summarize each bucket"
+    )]
+    Aggregate {
+        goal: String,
+        #[structopt(flatten)]
+        filters: DatapointFilters,
+        #[structopt(long, default_value = "day", about = "Bucket size: day, week, or month")]
+        bucket: analytics::Bucket,
+        #[structopt(
+            long,
+            default_value = "json",
+            about = "Output format: table, csv, or json"
+        )]
+        format: analytics::OutputFormat,
+    },
     #[structopt(name = "create", about = "Create a datapoint from CLI flags")]
     Create {
         goal: String,
@@ -47,6 +124,15 @@ enum DatapointCommand {
     Delete { goal: String, id: String },
 }
 
+#[derive(StructOpt)]
+enum AuthCommand {
+    #[structopt(
+        name = "check",
+        about = "Validate configured credentials and report where they came from"
+    )]
+    Check,
+}
+
 #[derive(StructOpt)]
 enum Command {
     #[structopt(name = "user", about = "Relates to a Beeminder user")]
@@ -58,6 +144,8 @@ enum Command {
         about = "Related to datapoints of a Beeminder user"
     )]
     Datapoint(DatapointCommand),
+    #[structopt(name = "auth", about = "Relates to authentication credentials")]
+    Auth(AuthCommand),
 }
 
 #[derive(StructOpt)]
@@ -77,6 +165,31 @@ struct Cli {
     username: Option<String>,
     #[structopt(subcommand)]
     cmd: Command,
+    #[structopt(
+        long,
+        about = "Serve reads only from the on-disk cache, erroring on a cache miss instead of hitting the network"
+    )]
+    offline: bool,
+    #[structopt(long, about = "Bypass the on-disk cache entirely for this invocation")]
+    no_cache: bool,
+    #[structopt(
+        long,
+        default_value = "300",
+        about = "How long a cached response stays fresh, in seconds"
+    )]
+    cache_ttl_secs: i64,
+    #[structopt(
+        long,
+        about = "Named credential profile to load from the config file. Defaults to \"default\""
+    )]
+    profile: Option<String>,
+    #[structopt(long, about = "HTTP(S) proxy URL to send requests through")]
+    proxy: Option<String>,
+    #[structopt(
+        long,
+        about = "Fixed IP address to use for www.beeminder.com, bypassing system DNS resolution"
+    )]
+    resolve: Option<std::net::IpAddr>,
 }
 
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
@@ -99,51 +212,6 @@ impl BeeminderUrl {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Auth {
-    username: Option<String>,
-    auth_token: Option<String>,
-}
-
-impl ::std::default::Default for Auth {
-    fn default() -> Self {
-        Self {
-            username: None,
-            auth_token: None,
-        }
-    }
-}
-
-impl Auth {
-    // Load authentication information with the following order of preference, descending:
-    //
-    // - Command line argument (i.e. --username or --auth_token).
-    // - Environment variable (i.e. BEEMINDER_USERNAME).
-    // - Configuration file.
-    fn load(args: &Cli) -> Result<Self> {
-        let token = {
-            if let Some(token) = &args.auth_token {
-                Some(token.to_string())
-            } else {
-                std::env::var("BEEMINDER_AUTH_TOKEN").ok()
-            }
-        };
-        let user = if let Some(user) = &args.username {
-            Some(user.to_string())
-        } else {
-            std::env::var("BEEMINDER_USERNAME").ok()
-        };
-        let mut auth: Auth = confy::load("beeminder")?;
-        if token.is_some() {
-            auth.auth_token = token;
-        }
-        if user.is_some() {
-            auth.username = user;
-        }
-        Ok(auth)
-    }
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 struct User {
     created_at: u64,
@@ -213,51 +281,208 @@ fn datapoints_from_stdin(goal: &str) -> Result<Vec<Datapoint>> {
     Ok(points)
 }
 
+// Cache policy shared by every `cached_get` call in a single invocation, grouped to keep
+// `cached_get` itself under clippy's argument-count limit.
+struct CacheOptions {
+    offline: bool,
+    no_cache: bool,
+    ttl: chrono::Duration,
+    retry_backoff: Option<std::time::Duration>,
+}
+
+// Fetches `key` from `cache` if it is fresh, otherwise issues `client.get(url)`, writing the
+// result through to the cache unless `no_cache` is set. Returns an error without touching
+// the network if `offline` is set and the cache misses.
+async fn cached_get<T>(
+    client: &reqwest::Client,
+    cache: &FileCache<T>,
+    key: &str,
+    url: &str,
+    options: &CacheOptions,
+) -> Result<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    if !options.no_cache {
+        match cache.lookup(key)? {
+            LookupStatus::Found(entry) if entry.is_fresh(options.ttl) => {
+                info!("Serving {} from cache", key);
+                return Ok(entry.data);
+            }
+            // Offline mode can't refresh a stale entry anyway, so serve it rather than erroring.
+            LookupStatus::Found(entry) if options.offline => {
+                info!("Serving stale cached {} (--offline was given)", key);
+                return Ok(entry.data);
+            }
+            _ => {}
+        }
+    }
+    if options.offline {
+        bail!("No cached data for {} and --offline was given", key);
+    }
+    let response = client::send_with_retry(|| client.get(url), options.retry_backoff).await?;
+    let data: T = response.json().await?;
+    let entry = CacheEntry::new(data);
+    if !options.no_cache {
+        cache.store(key, &entry)?;
+    }
+    Ok(entry.data)
+}
+
+// Resolves credentials, makes a lightweight authenticated request to the user endpoint, and
+// reports the outcome plus which source (flag, env var, or profile) the token came from.
+async fn run_auth_check(auth: &config::Auth, args: &Cli) -> Result<()> {
+    let user = match &auth.username {
+        Some(user) => user,
+        None => bail!(
+            "No username configured (checked command-line flag, BEEMINDER_USERNAME, and profile)"
+        ),
+    };
+    let token = match &auth.auth_token {
+        Some(token) => token,
+        None => bail!(
+            "No auth token configured (checked command-line flag, BEEMINDER_AUTH_TOKEN, and profile)"
+        ),
+    };
+    let url = BeeminderUrl::new(user, token);
+    let client = client::build_client(
+        APP_USER_AGENT,
+        auth.request_timeout,
+        args.proxy.as_deref(),
+        args.resolve,
+    )?;
+    let response = client.get(url.build(".json")).send().await?;
+    if response.status().is_success() {
+        println!(
+            "OK: authenticated as {} (username from {}, token from {})",
+            user, auth.username_source, auth.token_source
+        );
+        Ok(())
+    } else {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        bail!(
+            "Authentication failed for {} (token from {}): {} ({})",
+            user,
+            auth.token_source,
+            status,
+            body
+        );
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
     let args = Cli::from_args();
     let auth = Auth::load(&args)?;
     info!("Authentication information: {:?}", auth);
+    if let Command::Auth(AuthCommand::Check) = &args.cmd {
+        return run_auth_check(&auth, &args).await;
+    }
     let user = auth.username.expect("No Beeminder user provided.");
     let token = auth.auth_token.expect("No Beeminder token provided.");
     let url = BeeminderUrl::new(&user, &token);
-    let builder = reqwest::ClientBuilder::new();
-    let client = builder.user_agent(APP_USER_AGENT).build()?;
+    let client = client::build_client(
+        APP_USER_AGENT,
+        auth.request_timeout,
+        args.proxy.as_deref(),
+        args.resolve,
+    )?;
+    let retry_backoff = auth.retry_backoff;
+    let cache_dir = cache::cache_dir(&user)?;
+    let cache_options = CacheOptions {
+        offline: args.offline,
+        no_cache: args.no_cache,
+        ttl: chrono::Duration::seconds(args.cache_ttl_secs),
+        retry_backoff,
+    };
     match args.cmd {
+        Command::Auth(_) => unreachable!("handled above"),
         Command::User => {
             info!("Retrieving user data for {}", user);
-            let response = client.get(url.build(".json")).send().await?;
-            let user: User = response.json().await?;
+            let cache: FileCache<User> = FileCache::new(&cache_dir)?;
+            let user = cached_get(
+                &client,
+                &cache,
+                ".json",
+                &url.build(".json"),
+                &cache_options,
+            )
+            .await?;
             println!("{}", serde_json::to_string(&user).unwrap());
         }
         Command::Goal(cmd) => match cmd {
             GoalCommand::List => {
                 info!("Retrieving goals for user {}", user);
-                let response = client.get(url.build("/goals.json")).send().await?;
-                let goal: Vec<Goal> = response.json().await?;
+                let cache: FileCache<Vec<Goal>> = FileCache::new(&cache_dir)?;
+                let goal = cached_get(
+                    &client,
+                    &cache,
+                    "goals.json",
+                    &url.build("/goals.json"),
+                    &cache_options,
+                )
+                .await?;
                 println!("{}", serde_json::to_string(&goal).unwrap());
             }
             GoalCommand::Info { goal } => {
                 info!("Retrieving goal data for goal {} user {}", user, goal);
-                let response = client
-                    .get(url.build(&format!("/goals/{}.json", goal)))
-                    .send()
-                    .await?;
-                let goal: Goal = response.json().await?;
+                let cache: FileCache<Goal> = FileCache::new(&cache_dir)?;
+                let key = format!("goals_{}.json", goal);
+                let goal = cached_get(
+                    &client,
+                    &cache,
+                    &key,
+                    &url.build(&format!("/goals/{}.json", goal)),
+                    &cache_options,
+                )
+                .await?;
                 println!("{}", serde_json::to_string(&goal).unwrap());
             }
         },
         Command::Datapoint(cmd) => match cmd {
-            DatapointCommand::List { goal } => {
+            DatapointCommand::List { goal, filters } => {
                 info!("Retrieving datapoint data for goal {} user {}", goal, user);
-                let response = client
-                    .get(url.build(&format!("/goals/{}/datapoints.json", goal)))
-                    .send()
-                    .await?;
-                let datapoints: Vec<Datapoint> = response.json().await?;
+                let cache: FileCache<Vec<Datapoint>> = FileCache::new(&cache_dir)?;
+                let key = format!("goals_{}_datapoints.json", goal);
+                let datapoints = cached_get(
+                    &client,
+                    &cache,
+                    &key,
+                    &url.build(&format!("/goals/{}/datapoints.json", goal)),
+                    &cache_options,
+                )
+                .await?;
+                let datapoints =
+                    analytics::filter_datapoints(datapoints, &filters.into_filter()?);
                 println!("{}", serde_json::to_string(&datapoints).unwrap());
             }
+            DatapointCommand::Aggregate {
+                goal,
+                filters,
+                bucket,
+                format,
+            } => {
+                info!(
+                    "Aggregating datapoint data for goal {} user {}",
+                    goal, user
+                );
+                let cache: FileCache<Vec<Datapoint>> = FileCache::new(&cache_dir)?;
+                let key = format!("goals_{}_datapoints.json", goal);
+                let datapoints = cached_get(
+                    &client,
+                    &cache,
+                    &key,
+                    &url.build(&format!("/goals/{}/datapoints.json", goal)),
+                    &cache_options,
+                )
+                .await?;
+                let datapoints =
+                    analytics::filter_datapoints(datapoints, &filters.into_filter()?);
+                let aggregates = analytics::aggregate(&datapoints, bucket);
+                println!("{}", analytics::render(&aggregates, format)?);
+            }
             DatapointCommand::Create {
                 goal,
                 value,
@@ -267,6 +492,9 @@ async fn main() -> Result<()> {
                 request_id,
             } => {
                 info!("Creating new data point for goal {} user {}", goal, user);
+                // Only a request with a request_id is safe to retry: without one, a retry after
+                // a lost response would create a second, indistinguishable duplicate datapoint.
+                let is_retryable = request_id.is_some();
                 let mut params = vec![("value", value.to_string())];
                 if let Some(t) = timestamp {
                     params.push(("timestamp", t.to_string()));
@@ -280,13 +508,21 @@ async fn main() -> Result<()> {
                 if let Some(r) = request_id {
                     params.push(("requestid", r));
                 }
-                client
-                    .post(url.build(&format!("/goals/{}/datapoints.json", goal)))
-                    .form(&params)
-                    .header(reqwest::header::CONTENT_TYPE, "application/json")
-                    .header(reqwest::header::ACCEPT, "application/json")
-                    .send()
-                    .await?;
+                let create_url = url.build(&format!("/goals/{}/datapoints.json", goal));
+                let build_request = || {
+                    client
+                        .post(&create_url)
+                        .form(&params)
+                        .header(reqwest::header::CONTENT_TYPE, "application/json")
+                        .header(reqwest::header::ACCEPT, "application/json")
+                };
+                let response = if is_retryable {
+                    client::send_with_retry(build_request, retry_backoff).await?
+                } else {
+                    build_request().send().await?
+                };
+                client::check_status(response).await?;
+                invalidate_goal_cache(&cache_dir, &goal)?;
             }
             DatapointCommand::Put { goal } => {
                 info!(
@@ -294,22 +530,37 @@ async fn main() -> Result<()> {
                     goal, user
                 );
                 let points = datapoints_from_stdin(&goal)?;
-                client
+                let response = client
                     .post(url.build(&format!("/goals/{}/datapoints/create_all.json", goal)))
                     .form(&[("datapoints", serde_json::to_string(&points).unwrap())])
                     .header(reqwest::header::CONTENT_TYPE, "application/json")
                     .header(reqwest::header::ACCEPT, "application/json")
                     .send()
                     .await?;
+                client::check_status(response).await?;
+                invalidate_goal_cache(&cache_dir, &goal)?;
             }
             DatapointCommand::Delete { goal, id } => {
                 info!("Deleting data point {} for goal {} user {}", id, goal, user);
-                client
-                    .delete(url.build(&format!("/goals/{}/datapoints/{}.json", goal, id)))
-                    .send()
-                    .await?;
+                let delete_url = url.build(&format!("/goals/{}/datapoints/{}.json", goal, id));
+                let response =
+                    client::send_with_retry(|| client.delete(&delete_url), retry_backoff).await?;
+                client::check_status(response).await?;
+                invalidate_goal_cache(&cache_dir, &goal)?;
             }
         },
     }
     Ok(())
 }
+
+// Drops cached goal, goal-list, and datapoint-list entries for `goal` after a mutation so stale
+// `safesum`/`losedate` data isn't served from the cache on the next read.
+fn invalidate_goal_cache(cache_dir: &std::path::Path, goal: &str) -> Result<()> {
+    let goal_list_cache: FileCache<Vec<Goal>> = FileCache::new(cache_dir)?;
+    goal_list_cache.invalidate("goals.json")?;
+    let goal_cache: FileCache<Goal> = FileCache::new(cache_dir)?;
+    goal_cache.invalidate(&format!("goals_{}.json", goal))?;
+    let datapoints_cache: FileCache<Vec<Datapoint>> = FileCache::new(cache_dir)?;
+    datapoints_cache.invalidate(&format!("goals_{}_datapoints.json", goal))?;
+    Ok(())
+}